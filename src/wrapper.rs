@@ -1,5 +1,91 @@
 use std::borrow::Cow;
 
+// Compilation flags and resource limits threaded into `regex::RegexBuilder`
+// and `regex::RegexSetBuilder` by the `with_options` constructors. The size
+// limits are `Option` so that `None` leaves the `regex` crate's own default in
+// place; setting them lets an embedded extension cap the memory a single
+// hostile pattern can consume. `Default` mirrors the builders' defaults, so
+// `Regex::with_options(re, RegexOptions::default())` matches `Regex::new(re)`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct RegexOptions {
+    pub case_insensitive: bool,
+    pub multi_line: bool,
+    pub dot_matches_new_line: bool,
+    pub unicode: bool,
+    pub size_limit: Option<usize>,
+    pub dfa_size_limit: Option<usize>,
+}
+
+impl Default for RegexOptions {
+    fn default() -> Self {
+        RegexOptions {
+            case_insensitive: false,
+            multi_line: false,
+            dot_matches_new_line: false,
+            unicode: true,
+            size_limit: None,
+            dfa_size_limit: None,
+        }
+    }
+}
+
+// Default capacity of the per-thread compiled-pattern cache. Override with
+// [`set_cache_capacity`] before the first cached lookup on a given thread.
+pub const DEFAULT_CACHE_CAPACITY: usize = 32;
+
+// Minimal least-recently-used map from a `(pattern, options)` key to an already
+// built [`Regex`]. Entries are ordered oldest-first; a hit moves its entry to
+// the back, and inserts past `capacity` evict from the front. The invalid
+// (`None`) result is cached like any other, so the `ignore-invalid` fallback is
+// not re-attempted on every row for a repeated bad pattern.
+struct RegexCache {
+    entries: Vec<((String, RegexOptions), Regex)>,
+    capacity: usize,
+}
+
+impl RegexCache {
+    fn new() -> Self {
+        RegexCache {
+            entries: Vec::new(),
+            capacity: DEFAULT_CACHE_CAPACITY,
+        }
+    }
+
+    fn get(&mut self, key: &(String, RegexOptions)) -> Option<Regex> {
+        let pos = self.entries.iter().position(|(k, _)| k == key)?;
+        let entry = self.entries.remove(pos);
+        let regex = entry.1.clone();
+        self.entries.push(entry);
+        Some(regex)
+    }
+
+    fn insert(&mut self, key: (String, RegexOptions), regex: Regex) {
+        if let Some(pos) = self.entries.iter().position(|(k, _)| *k == key) {
+            self.entries.remove(pos);
+        }
+        self.entries.push((key, regex));
+        while self.entries.len() > self.capacity {
+            self.entries.remove(0);
+        }
+    }
+}
+
+thread_local! {
+    static REGEX_CACHE: std::cell::RefCell<RegexCache> = std::cell::RefCell::new(RegexCache::new());
+}
+
+// Set the capacity of the calling thread's compiled-pattern cache, evicting the
+// least-recently-used entries if the new capacity is smaller.
+pub fn set_cache_capacity(capacity: usize) {
+    REGEX_CACHE.with(|cache| {
+        let mut cache = cache.borrow_mut();
+        cache.capacity = capacity;
+        while cache.entries.len() > capacity {
+            cache.entries.remove(0);
+        }
+    });
+}
+
 // Regex wrapper class that allows for unified management
 // of invalid regex patterns
 #[derive(Clone)]
@@ -15,6 +101,46 @@ impl Regex {
         Ok(Regex(regex))
     }
 
+    pub fn cached(re: &str) -> Result<Regex, regex::Error> {
+        Regex::cached_with_options(re, RegexOptions::default())
+    }
+
+    pub fn cached_with_options(re: &str, opts: RegexOptions) -> Result<Regex, regex::Error> {
+        let key = (re.to_owned(), opts);
+        REGEX_CACHE.with(|cache| {
+            let mut cache = cache.borrow_mut();
+            if let Some(regex) = cache.get(&key) {
+                // Hit: the wrapped `regex::Regex` is `Arc`-backed, so this
+                // clone is cheap and shares the compiled program.
+                return Ok(regex.clone());
+            }
+            let regex = Regex::with_options(&key.0, key.1.clone())?;
+            cache.insert(key, regex.clone());
+            Ok(regex)
+        })
+    }
+
+    pub fn with_options(re: &str, opts: RegexOptions) -> Result<Regex, regex::Error> {
+        let mut builder = regex::RegexBuilder::new(re);
+        builder
+            .case_insensitive(opts.case_insensitive)
+            .multi_line(opts.multi_line)
+            .dot_matches_new_line(opts.dot_matches_new_line)
+            .unicode(opts.unicode);
+        if let Some(limit) = opts.size_limit {
+            builder.size_limit(limit);
+        }
+        if let Some(limit) = opts.dfa_size_limit {
+            builder.dfa_size_limit(limit);
+        }
+        let regex = match builder.build() {
+            Ok(r) => Some(r),
+            Err(_) if cfg!(feature = "ignore-invalid") => None,
+            Err(e) => return Err(e),
+        };
+        Ok(Regex(regex))
+    }
+
     #[inline(always)]
     pub fn find<'h>(&self, haystack: &'h str) -> Option<regex::Match<'h>> {
         self.0.as_ref().and_then(|r| r.find(haystack))
@@ -87,12 +213,27 @@ impl Regex {
             .as_ref()
             .map_or_else(OptionIter::none, |r| OptionIter::some(r.capture_names()))
     }
+
+    // Report whether the pattern compiled. Under the `ignore-invalid` feature a
+    // bad pattern is collapsed to `None` and never matches; this distinguishes
+    // that case from a valid pattern that simply has no matches.
+    #[inline(always)]
+    pub fn is_valid(&self) -> bool {
+        self.0.is_some()
+    }
 }
 
 // RegexSet wrapper class that allows for unified management
-// of invalid regex patterns
+// of invalid regex patterns. The original input expressions and a parallel
+// validity mask are retained so that, under the `ignore-invalid` feature, the
+// exact patterns that failed to compile can still be enumerated after the
+// compiled set collapses to `None`.
 #[derive(Clone)]
-pub struct RegexSet(Option<regex::RegexSet>);
+pub struct RegexSet {
+    set: Option<regex::RegexSet>,
+    patterns: Vec<String>,
+    valid: Vec<bool>,
+}
 
 impl RegexSet {
     pub fn new<I, S>(exprs: I) -> Result<RegexSet, regex::Error>
@@ -100,26 +241,248 @@ impl RegexSet {
         S: AsRef<str>,
         I: IntoIterator<Item = S>,
     {
-        let regex = match regex::RegexSet::new(exprs) {
+        let patterns: Vec<String> = exprs.into_iter().map(|s| s.as_ref().to_owned()).collect();
+        let set = match regex::RegexSet::new(&patterns) {
+            Ok(r) => Some(r),
+            Err(_) if cfg!(feature = "ignore-invalid") => None,
+            Err(e) => return Err(e),
+        };
+        let valid = validity_mask(&patterns, &RegexOptions::default(), set.is_some());
+        Ok(RegexSet {
+            set,
+            patterns,
+            valid,
+        })
+    }
+
+    pub fn with_options<I, S>(exprs: I, opts: RegexOptions) -> Result<RegexSet, regex::Error>
+    where
+        S: AsRef<str>,
+        I: IntoIterator<Item = S>,
+    {
+        let patterns: Vec<String> = exprs.into_iter().map(|s| s.as_ref().to_owned()).collect();
+        let mut builder = regex::RegexSetBuilder::new(&patterns);
+        builder
+            .case_insensitive(opts.case_insensitive)
+            .multi_line(opts.multi_line)
+            .dot_matches_new_line(opts.dot_matches_new_line)
+            .unicode(opts.unicode);
+        if let Some(limit) = opts.size_limit {
+            builder.size_limit(limit);
+        }
+        if let Some(limit) = opts.dfa_size_limit {
+            builder.dfa_size_limit(limit);
+        }
+        let set = match builder.build() {
             Ok(r) => Some(r),
             Err(_) if cfg!(feature = "ignore-invalid") => None,
             Err(e) => return Err(e),
         };
-        Ok(RegexSet(regex))
+        let valid = validity_mask(&patterns, &opts, set.is_some());
+        Ok(RegexSet {
+            set,
+            patterns,
+            valid,
+        })
     }
 
     #[inline(always)]
     pub fn patterns(&self) -> &[String] {
-        self.0.as_ref().map_or(&[][..], |r| r.patterns())
+        &self.patterns
     }
 
     #[inline(always)]
     pub fn is_match(&self, haystack: &str) -> bool {
-        self.0.as_ref().map_or(false, |r| r.is_match(haystack))
+        self.set.as_ref().map_or(false, |r| r.is_match(haystack))
     }
 
     #[inline(always)]
     pub fn matches(&self, haystack: &str) -> OptionIntoIter<regex::SetMatches> {
+        self.set.as_ref().map_or_else(OptionIntoIter::none, |r| {
+            OptionIntoIter::some(r.matches(haystack))
+        })
+    }
+
+    // Enumerate the input patterns that failed to compile. When every pattern
+    // is valid this yields nothing; under `ignore-invalid` it surfaces exactly
+    // the expressions that fail to compile on their own (the whole set then
+    // matches nothing), so a companion SQL function can report them instead of
+    // leaving the failure silent.
+    #[inline(always)]
+    pub fn invalid_patterns(&self) -> OptionIter<InvalidPatterns<'_>> {
+        OptionIter::some(InvalidPatterns {
+            patterns: self.patterns.iter(),
+            valid: self.valid.iter(),
+        })
+    }
+}
+
+// Compute the per-pattern validity mask. If the combined set compiled, every
+// pattern is necessarily valid; otherwise each expression is recompiled on its
+// own under the same options to find which ones were at fault.
+fn validity_mask(patterns: &[String], opts: &RegexOptions, set_ok: bool) -> Vec<bool> {
+    if set_ok {
+        return vec![true; patterns.len()];
+    }
+    patterns
+        .iter()
+        .map(|p| Regex::with_options(p, opts.clone()).map_or(false, |r| r.is_valid()))
+        .collect()
+}
+
+// Iterator over the invalid patterns of a `RegexSet`, walking the retained
+// input expressions in lockstep with their validity mask.
+pub struct InvalidPatterns<'a> {
+    patterns: std::slice::Iter<'a, String>,
+    valid: std::slice::Iter<'a, bool>,
+}
+
+impl<'a> Iterator for InvalidPatterns<'a> {
+    type Item = &'a String;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let pattern = self.patterns.next()?;
+            let valid = self.valid.next()?;
+            if !*valid {
+                return Some(pattern);
+            }
+        }
+    }
+}
+
+// Byte-oriented Regex wrapper, mirroring `Regex` but built on
+// `regex::bytes::Regex` so that SQLite BLOB arguments and other non-UTF-8
+// haystacks can be matched without a lossy conversion to `&str`.
+#[derive(Clone)]
+pub struct BytesRegex(Option<regex::bytes::Regex>);
+
+impl BytesRegex {
+    pub fn new(re: &str) -> Result<BytesRegex, regex::Error> {
+        let regex = match regex::bytes::Regex::new(re) {
+            Ok(r) => Some(r),
+            Err(_) if cfg!(feature = "ignore-invalid") => None,
+            Err(e) => return Err(e),
+        };
+        Ok(BytesRegex(regex))
+    }
+
+    #[inline(always)]
+    pub fn find<'h>(&self, haystack: &'h [u8]) -> Option<regex::bytes::Match<'h>> {
+        self.0.as_ref().and_then(|r| r.find(haystack))
+    }
+
+    #[inline(always)]
+    pub fn find_at<'h>(&self, haystack: &'h [u8], start: usize) -> Option<regex::bytes::Match<'h>> {
+        self.0.as_ref().and_then(|r| r.find_at(haystack, start))
+    }
+
+    #[inline(always)]
+    pub fn find_iter<'r, 'h>(
+        &'r self,
+        haystack: &'h [u8],
+    ) -> OptionIter<regex::bytes::Matches<'r, 'h>> {
+        self.0.as_ref().map_or_else(OptionIter::none, |r| {
+            OptionIter::some(r.find_iter(haystack))
+        })
+    }
+
+    #[inline(always)]
+    pub fn split<'r, 'h>(&'r self, haystack: &'h [u8]) -> OptionIter<regex::bytes::Split<'r, 'h>>
+    where
+        'h: 'r,
+    {
+        self.0
+            .as_ref()
+            .map_or(OptionIter::none(), |r| OptionIter::some(r.split(haystack)))
+    }
+
+    #[inline(always)]
+    pub fn as_str(&self) -> &str {
+        self.0.as_ref().map_or("invalid", |r| r.as_str())
+    }
+
+    #[inline(always)]
+    pub fn is_match(&self, haystack: &[u8]) -> bool {
+        self.0.as_ref().map_or(false, |r| r.is_match(haystack))
+    }
+
+    #[inline(always)]
+    pub fn replace<'h, R: regex::bytes::Replacer>(
+        &self,
+        haystack: &'h [u8],
+        rep: R,
+    ) -> Cow<'h, [u8]> {
+        self.0
+            .as_ref()
+            .map_or_else(|| Cow::Borrowed(haystack), |r| r.replace(haystack, rep))
+    }
+
+    #[inline(always)]
+    pub fn replace_all<'h, R: regex::bytes::Replacer>(
+        &self,
+        haystack: &'h [u8],
+        rep: R,
+    ) -> Cow<'h, [u8]> {
+        self.0
+            .as_ref()
+            .map_or_else(|| Cow::Borrowed(haystack), |r| r.replace_all(haystack, rep))
+    }
+
+    #[inline(always)]
+    pub fn captures<'h>(&self, haystack: &'h [u8]) -> Option<regex::bytes::Captures<'h>> {
+        self.0.as_ref().and_then(|r| r.captures(haystack))
+    }
+
+    #[inline(always)]
+    pub fn captures_iter<'r, 'h>(
+        &'r self,
+        haystack: &'h [u8],
+    ) -> OptionIter<regex::bytes::CaptureMatches<'r, 'h>> {
+        self.0.as_ref().map_or_else(OptionIter::none, |r| {
+            OptionIter::some(r.captures_iter(haystack))
+        })
+    }
+
+    #[inline(always)]
+    pub fn capture_names(&self) -> OptionIter<regex::bytes::CaptureNames> {
+        self.0
+            .as_ref()
+            .map_or_else(OptionIter::none, |r| OptionIter::some(r.capture_names()))
+    }
+}
+
+// Byte-oriented RegexSet wrapper, the `regex::bytes::RegexSet` counterpart to
+// `RegexSet`.
+#[derive(Clone)]
+pub struct BytesRegexSet(Option<regex::bytes::RegexSet>);
+
+impl BytesRegexSet {
+    pub fn new<I, S>(exprs: I) -> Result<BytesRegexSet, regex::Error>
+    where
+        S: AsRef<str>,
+        I: IntoIterator<Item = S>,
+    {
+        let regex = match regex::bytes::RegexSet::new(exprs) {
+            Ok(r) => Some(r),
+            Err(_) if cfg!(feature = "ignore-invalid") => None,
+            Err(e) => return Err(e),
+        };
+        Ok(BytesRegexSet(regex))
+    }
+
+    #[inline(always)]
+    pub fn patterns(&self) -> &[String] {
+        self.0.as_ref().map_or(&[][..], |r| r.patterns())
+    }
+
+    #[inline(always)]
+    pub fn is_match(&self, haystack: &[u8]) -> bool {
+        self.0.as_ref().map_or(false, |r| r.is_match(haystack))
+    }
+
+    #[inline(always)]
+    pub fn matches(&self, haystack: &[u8]) -> OptionIntoIter<regex::bytes::SetMatches> {
         self.0.as_ref().map_or_else(OptionIntoIter::none, |r| {
             OptionIntoIter::some(r.matches(haystack))
         })